@@ -1,8 +1,10 @@
 use super::{Context, Mapping, Mmap, Path, Stash, Vec};
 use core::convert::TryInto;
 use object::macho;
-use object::read::macho::{MachHeader, Nlist, Section, Segment as _};
+use object::read::macho::{DyldCache, MachHeader, Nlist, Section, Segment as _};
 use object::{Bytes, NativeEndian};
+use std::collections::HashMap;
+use std::path::PathBuf;
 
 #[cfg(target_pointer_width = "32")]
 type Mach = object::macho::MachHeader32<NativeEndian>;
@@ -17,6 +19,18 @@ impl Mapping {
     // different implementation of the function here. On OSX we need to go
     // probing the filesystem for a bunch of files.
     pub fn new(path: &Path) -> Option<Mapping> {
+        if let Some(mapping) = Mapping::from_file(path) {
+            return Some(mapping);
+        }
+
+        // Most system frameworks and libraries no longer exist as
+        // standalone files on modern macOS -- they live only inside the
+        // dyld shared cache. If we couldn't map `path` directly, see if
+        // it's an image within the cache instead.
+        Mapping::from_dyld_shared_cache(path)
+    }
+
+    fn from_file(path: &Path) -> Option<Mapping> {
         // First up we need to load the unique UUID which is stored in the macho
         // header of the file we're reading, specified at `path`.
         let map = super::mmap(path)?;
@@ -50,7 +64,7 @@ impl Mapping {
         // file. This should have the symbol table for at least some
         // symbolication purposes.
         let stash = Stash::new();
-        let inner = super::cx(&stash, Object::parse(macho, endian, data)?)?;
+        let inner = super::cx(&stash, Object::parse(macho, endian, data, data)?)?;
         return Some(mk!(Mapping { map, inner, stash }));
 
         fn load_dsym(dir: &Path, uuid: [u8; 16]) -> Option<Mapping> {
@@ -65,7 +79,7 @@ impl Mapping {
                 }
                 let stash = Stash::new();
                 if let Some(cx) =
-                    Object::parse(macho, endian, data).and_then(|o| super::cx(&stash, o))
+                    Object::parse(macho, endian, data, data).and_then(|o| super::cx(&stash, o))
                 {
                     return Some(mk!(Mapping { map, cx, stash }));
                 }
@@ -74,24 +88,206 @@ impl Mapping {
             None
         }
     }
-}
 
-fn find_header(mut data: Bytes<'_>) -> Option<(&'_ Mach, Bytes<'_>)> {
-    use object::endian::BigEndian;
+    // Locate `path` as an image inside the dyld shared cache for the
+    // current process's architecture and build a `Mapping` directly from
+    // its mapped byte range. This is the common case for system frameworks
+    // on recent macOS, which are only ever shipped inside the cache.
+    fn from_dyld_shared_cache(path: &Path) -> Option<Mapping> {
+        let install_name = path.to_str()?;
+        let cache_path = dyld_shared_cache_path()?;
+        // Since macOS Catalina the cache is split across the primary file
+        // and numbered `.01`, `.02`, ... (and `.symbols`) sibling files;
+        // `DyldCache::parse` needs all of their bytes to resolve an
+        // image's mapped ranges.
+        let cache_paths = dyld_shared_cache_file_paths(&cache_path);
+        let map = super::mmap(&cache_path)?;
+        // `DyldCache::parse` matches `subcache_data` positionally against
+        // the cache's own declared subcache list, so a subcache we failed
+        // to map has to abort the whole lookup rather than be silently
+        // dropped -- leaving it out would shift every later subcache into
+        // the wrong slot instead of just missing the one that's unreadable.
+        let subcache_maps: Vec<Mmap> = cache_paths[1..]
+            .iter()
+            .map(|p| super::mmap(p))
+            .collect::<Option<_>>()?;
+        let subcache_data: Vec<&[u8]> = subcache_maps.iter().map(|m| &m[..]).collect();
+        let cache = DyldCache::<NativeEndian>::parse(&map[..], subcache_data).ok()?;
+        let image = cache
+            .images()
+            .find(|image| image.path() == Ok(install_name))?;
+        let (image_data, _address) = image.data_and_offset().ok()?;
 
-    let desired_cpu = || {
-        if cfg!(target_arch = "x86") {
-            Some(macho::CPU_TYPE_X86)
-        } else if cfg!(target_arch = "x86_64") {
-            Some(macho::CPU_TYPE_X86_64)
-        } else if cfg!(target_arch = "arm") {
-            Some(macho::CPU_TYPE_ARM)
-        } else if cfg!(target_arch = "aarch64") {
-            Some(macho::CPU_TYPE_ARM64)
+        // `image_data` borrows from whichever file actually holds this
+        // image (the primary cache or one of the subcaches above); work
+        // out which one and re-map just that file as the long-lived
+        // mapping this `Mapping` owns.
+        let (owning_path, offset) = if let Some(offset) = slice_offset(&map, image_data) {
+            (cache_path, offset)
         } else {
-            None
-        }
+            let (index, offset) = subcache_maps
+                .iter()
+                .enumerate()
+                .find_map(|(i, m)| slice_offset(m, image_data).map(|offset| (i, offset)))?;
+            (cache_paths[index + 1].clone(), offset)
+        };
+
+        let map = super::mmap(&owning_path)?;
+        let header_data = Bytes(&map).read_bytes_at(offset, image_data.len()).ok()?;
+        let (macho, commands) = find_header(header_data)?;
+        let endian = macho.endian().ok()?;
+        let stash = Stash::new();
+        // Unlike a standalone file or a fat-arch slice, a dyld-cache
+        // image's embedded Mach-O isn't self-contained: the file offsets
+        // in its load commands (segment `fileoff`s, `LC_SYMTAB`,
+        // `LC_FUNCTION_STARTS`, `LC_DYLD_INFO`) are relative to the cache
+        // file as a whole, not to wherever this image's header happens to
+        // land inside it. So `Object::parse` walks `commands` (rooted at
+        // the header, just to find the command list) but resolves their
+        // offsets against `data`, the whole owning file.
+        let data = Bytes(&map);
+        let inner = super::cx(&stash, Object::parse(macho, endian, commands, data)?)?;
+        Some(mk!(Mapping { map, inner, stash }))
+    }
+}
+
+// Returns the path to the dyld shared cache file for the architecture of
+// the process we're symbolicating, e.g.
+// `/System/Library/dyld/dyld_shared_cache_arm64e`.
+fn dyld_shared_cache_path() -> Option<PathBuf> {
+    let arch = if cfg!(target_arch = "x86_64") {
+        "x86_64"
+    } else if cfg!(target_arch = "aarch64") {
+        "arm64e"
+    } else {
+        return None;
+    };
+    let path = PathBuf::from(format!("/System/Library/dyld/dyld_shared_cache_{}", arch));
+    if path.exists() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+// Returns `primary` followed by any sibling subcache files next to it,
+// e.g. `dyld_shared_cache_arm64e.01`, `.02`, `.symbols`, sorted by name.
+fn dyld_shared_cache_file_paths(primary: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![primary.to_path_buf()];
+    let (parent, file_name) = match (primary.parent(), primary.file_name().and_then(|f| f.to_str())) {
+        (Some(parent), Some(file_name)) => (parent, file_name),
+        _ => return paths,
     };
+    let prefix = format!("{}.", file_name);
+    let mut siblings: Vec<PathBuf> = parent
+        .read_dir()
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|p| {
+            p.file_name()
+                .and_then(|f| f.to_str())
+                .map_or(false, |name| name.starts_with(&prefix))
+        })
+        .collect();
+    siblings.sort();
+    paths.extend(siblings);
+    paths
+}
+
+// If `slice` borrows from within `container`, returns its byte offset.
+fn slice_offset(container: &[u8], slice: &[u8]) -> Option<usize> {
+    let container_start = container.as_ptr() as usize;
+    let container_end = container_start + container.len();
+    let slice_start = slice.as_ptr() as usize;
+    let slice_end = slice_start + slice.len();
+    if slice_start >= container_start && slice_end <= container_end {
+        Some(slice_start - container_start)
+    } else {
+        None
+    }
+}
+
+fn desired_cpu() -> Option<u32> {
+    if cfg!(target_arch = "x86") {
+        Some(macho::CPU_TYPE_X86)
+    } else if cfg!(target_arch = "x86_64") {
+        Some(macho::CPU_TYPE_X86_64)
+    } else if cfg!(target_arch = "arm") {
+        Some(macho::CPU_TYPE_ARM)
+    } else if cfg!(target_arch = "aarch64") {
+        Some(macho::CPU_TYPE_ARM64)
+    } else {
+        None
+    }
+}
+
+// The `cpu_subtype` of the Mach-O slice that matches how the current
+// process itself was launched, e.g. `arm64e` rather than plain `arm64` on
+// Apple Silicon. Rather than re-deriving this by guessing through our own
+// executable's on-disk fat header (ambiguous if it has multiple slices
+// for the same `cputype`), ask dyld directly for the in-memory header of
+// the main executable it already loaded -- that's literally the slice the
+// kernel picked, no guessing involved.
+fn desired_cpu_subtype() -> Option<u32> {
+    extern "C" {
+        // Declared in <mach-o/getsect.h>; returns the in-memory Mach-O
+        // header of this process's main executable.
+        fn _NSGetMachExecuteHeader() -> *const Mach;
+    }
+    let header = unsafe { _NSGetMachExecuteHeader().as_ref() }?;
+    let endian = header.endian().ok()?;
+    Some(header.cpusubtype(endian))
+}
+
+// The generic `_ALL` subtype for `desired_cpu()`'s cputype, e.g.
+// `CPU_SUBTYPE_ARM64_ALL` on aarch64. Used by `pick_fat_arch` as a
+// fallback that's architecture-agnostic, unlike hardcoding any one of
+// these constants would be.
+fn desired_cpu_subtype_all() -> Option<u32> {
+    if cfg!(target_arch = "x86") {
+        Some(macho::CPU_SUBTYPE_X86_ALL as u32)
+    } else if cfg!(target_arch = "x86_64") {
+        Some(macho::CPU_SUBTYPE_X86_64_ALL as u32)
+    } else if cfg!(target_arch = "arm") {
+        Some(macho::CPU_SUBTYPE_ARM_ALL as u32)
+    } else if cfg!(target_arch = "aarch64") {
+        Some(macho::CPU_SUBTYPE_ARM64_ALL as u32)
+    } else {
+        None
+    }
+}
+
+// Picks the best-matching slice out of a fat binary's `(cputype,
+// cpusubtype, offset, size)` tuples: an exact `(cputype, cpusubtype)`
+// match for the running process wins, masking off the capability bits in
+// `CPU_SUBTYPE_MASK`; otherwise fall back to the generic `_ALL` subtype,
+// or failing that just the first slice with a matching `cputype`.
+fn pick_fat_arch(archs: impl Iterator<Item = (u32, u32, u64, u64)>) -> Option<(u64, u64)> {
+    let desired_cpu = desired_cpu()?;
+    let desired_subtype =
+        desired_cpu_subtype().map(|subtype| subtype & !(macho::CPU_SUBTYPE_MASK as u32));
+    let desired_subtype_all = desired_cpu_subtype_all();
+
+    let mut fallback = None;
+    for (cputype, cpusubtype, offset, size) in archs {
+        if cputype != desired_cpu {
+            continue;
+        }
+        let subtype = cpusubtype & !(macho::CPU_SUBTYPE_MASK as u32);
+        if Some(subtype) == desired_subtype {
+            return Some((offset, size));
+        }
+        if fallback.is_none() || Some(subtype) == desired_subtype_all {
+            fallback = Some((offset, size));
+        }
+    }
+    fallback
+}
+
+fn find_header(mut data: Bytes<'_>) -> Option<(&'_ Mach, Bytes<'_>)> {
+    use object::endian::BigEndian;
 
     match data
         .clone()
@@ -106,11 +302,17 @@ fn find_header(mut data: Bytes<'_>) -> Option<(&'_ Mach, Bytes<'_>)> {
             let endian = BigEndian;
             let header = header_data.read::<macho::FatHeader>().ok()?;
             let nfat = header.nfat_arch.get(endian);
-            let arch = (0..nfat)
+            let archs: Vec<_> = (0..nfat)
                 .filter_map(|_| header_data.read::<macho::FatArch32>().ok())
-                .find(|arch| desired_cpu() == Some(arch.cputype.get(endian)))?;
-            let offset = arch.offset.get(endian);
-            let size = arch.size.get(endian);
+                .collect();
+            let (offset, size) = pick_fat_arch(archs.iter().map(|arch| {
+                (
+                    arch.cputype.get(endian),
+                    arch.cpusubtype.get(endian),
+                    u64::from(arch.offset.get(endian)),
+                    u64::from(arch.size.get(endian)),
+                )
+            }))?;
             data = data
                 .read_bytes_at(offset.try_into().ok()?, size.try_into().ok()?)
                 .ok()?;
@@ -121,11 +323,17 @@ fn find_header(mut data: Bytes<'_>) -> Option<(&'_ Mach, Bytes<'_>)> {
             let endian = BigEndian;
             let header = header_data.read::<macho::FatHeader>().ok()?;
             let nfat = header.nfat_arch.get(endian);
-            let arch = (0..nfat)
+            let archs: Vec<_> = (0..nfat)
                 .filter_map(|_| header_data.read::<macho::FatArch64>().ok())
-                .find(|arch| desired_cpu() == Some(arch.cputype.get(endian)))?;
-            let offset = arch.offset.get(endian);
-            let size = arch.size.get(endian);
+                .collect();
+            let (offset, size) = pick_fat_arch(archs.iter().map(|arch| {
+                (
+                    arch.cputype.get(endian),
+                    arch.cpusubtype.get(endian),
+                    arch.offset.get(endian),
+                    arch.size.get(endian),
+                )
+            }))?;
             data = data
                 .read_bytes_at(offset.try_into().ok()?, size.try_into().ok()?)
                 .ok()?;
@@ -142,23 +350,66 @@ pub struct Object<'a> {
     data: Bytes<'a>,
     dwarf: Option<&'a [MachSection]>,
     syms: Vec<(&'a [u8], u64)>,
+    func_starts: Vec<u64>,
     object_map: Option<object::ObjectMap<'a>>,
     object_mappings: Vec<Option<Option<Mapping>>>,
+    // Archives we've already indexed while resolving `object_map` entries,
+    // keyed by archive path, with a member-name -> (offset, size) index
+    // built from one scan of `members()`, so repeated lookups into the
+    // same `.a` skip the linear member scan. `Mapping::map` is a uniquely
+    // owned `Mmap` (defined in `mod.rs`), so unlike this index the mmap
+    // itself can't be cached and shared here without changing that type;
+    // each lookup still re-`mmap`s the archive to hand `Mapping` its own
+    // copy.
+    archives: Vec<ArchiveCache>,
+}
+
+struct ArchiveCache {
+    path: Vec<u8>,
+    members: HashMap<Vec<u8>, (u64, u64)>,
 }
 
 impl<'a> Object<'a> {
-    fn parse(mach: &'a Mach, endian: NativeEndian, data: Bytes<'a>) -> Option<Object<'a>> {
+    // `commands` is the buffer rooted at `mach`'s own header, used only to
+    // locate the load command list that directly follows it. `data` is the
+    // buffer every *file offset* embedded in those commands (segment
+    // `fileoff`s, `LC_SYMTAB`/`LC_FUNCTION_STARTS`/`LC_DYLD_INFO` offsets)
+    // is relative to. For a standalone file or a fat-arch slice those are
+    // the same buffer, since the Mach-O is self-contained and its offsets
+    // are relative to its own header; for an image inside the dyld shared
+    // cache they're not, since those offsets are relative to the cache
+    // file as a whole rather than to wherever the image's header landed
+    // inside it (see `from_dyld_shared_cache`), so callers there pass the
+    // whole owning cache file as `data` while `commands` stays rooted at
+    // the image header.
+    fn parse(
+        mach: &'a Mach,
+        endian: NativeEndian,
+        commands: Bytes<'a>,
+        data: Bytes<'a>,
+    ) -> Option<Object<'a>> {
         let is_object = mach.filetype(endian) == object::macho::MH_OBJECT;
         let mut dwarf = None;
         let mut syms = Vec::new();
-        let mut commands = mach.load_commands(endian, data).ok()?;
+        let mut commands = mach.load_commands(endian, commands).ok()?;
         let mut object_map = None;
         let mut object_mappings = Vec::new();
+        let mut text_vmaddr = 0;
+        let mut export_trie = None;
+        let mut function_starts_data = None;
         while let Ok(Some(command)) = commands.next() {
             if let Some((segment, section_data)) = MachSegment::from_command(command).ok()? {
+                if segment.name() == b"__TEXT" {
+                    text_vmaddr = segment.address(endian);
+                }
                 if segment.name() == b"__DWARF" || (is_object && segment.name() == b"") {
                     dwarf = segment.sections(endian, section_data).ok();
                 }
+            } else if command.cmd() == macho::LC_FUNCTION_STARTS {
+                let linkedit = command.data::<macho::LinkeditDataCommand<NativeEndian>>().ok()?;
+                let off = linkedit.dataoff.get(endian);
+                let size = linkedit.datasize.get(endian);
+                function_starts_data = data.read_bytes_at(off as usize, size as usize).ok();
             } else if let Some(symtab) = command.symtab().ok()? {
                 let symbols = symtab.symbols::<Mach>(endian, data).ok()?;
                 syms = symbols
@@ -178,16 +429,38 @@ impl<'a> Object<'a> {
                     object_mappings = vec![None; map.objects.len()];
                     object_map = Some(map);
                 }
+            } else if let Some(dyld_info) = command.dyld_info().ok()? {
+                let off = dyld_info.export_off.get(endian);
+                let size = dyld_info.export_size.get(endian);
+                if size > 0 {
+                    export_trie = data.read_bytes_at(off as usize, size as usize).ok();
+                }
             }
         }
 
+        // Many shipped dylibs are stripped of their classic nlist symbol
+        // table but still carry exports in the compressed export trie, so
+        // always try to fold those in alongside (or instead of) `syms`.
+        if let Some(trie) = export_trie {
+            let mut prefix = Vec::new();
+            let mut visited = Vec::new();
+            walk_export_trie(trie, text_vmaddr, 0, &mut prefix, &mut visited, &mut syms);
+            syms.sort_unstable_by_key(|(_, addr)| *addr);
+        }
+
+        let func_starts = function_starts_data
+            .map(|data| parse_function_starts(data, text_vmaddr))
+            .unwrap_or_default();
+
         Some(Object {
             endian,
             data,
             dwarf,
             syms,
+            func_starts,
             object_map,
             object_mappings,
+            archives: Vec::new(),
         })
     }
 
@@ -210,19 +483,36 @@ impl<'a> Object<'a> {
             Ok(i) => i,
             Err(i) => i.checked_sub(1)?,
         };
-        let (sym, _addr) = self.syms.get(i)?;
+        let (sym, sym_addr) = self.syms.get(i)?;
+        if let Some(end) = self.function_end(*sym_addr) {
+            if addr >= end {
+                return None;
+            }
+        }
         Some(sym)
     }
 
-    pub(super) fn search_object_map(&self, addr: u64) -> Option<(&Context<'_>, u64)> {
+    // The address one past the end of the function starting at `addr`,
+    // derived from LC_FUNCTION_STARTS, if we have one. This bounds
+    // `search_symtab` so an address in the padding after a short function
+    // doesn't get attributed to whatever symbol happens to precede it.
+    fn function_end(&self, addr: u64) -> Option<u64> {
+        match self.func_starts.binary_search(&addr) {
+            Ok(i) => self.func_starts.get(i + 1).copied(),
+            Err(i) => self.func_starts.get(i).copied(),
+        }
+    }
+
+    pub(super) fn search_object_map(&mut self, addr: u64) -> Option<(&Context<'_>, u64)> {
         let object_map = self.object_map.as_ref()?;
         let symbol = object_map.get(addr)?;
         let object_index = symbol.object_index();
-        let mapping = self.object_mappings.get_mut(object_index)?;
-        if mapping.is_none() {
-            *mapping = Some(object_mapping(object_map.object(object_index)?));
+        let path = object_map.object(object_index)?;
+        if self.object_mappings.get(object_index)?.is_none() {
+            let mapping = self.object_mapping(path);
+            *self.object_mappings.get_mut(object_index)? = Some(mapping);
         }
-        let cx = mapping.as_ref()?;
+        let cx = self.object_mappings.get(object_index)?.as_ref()?;
         for object_symbol in &cx.object.syms {
             if object_symbol.0 == symbol.name() {
                 let object_addr = addr
@@ -233,33 +523,56 @@ impl<'a> Object<'a> {
         }
         None
     }
-}
 
-fn object_mapping(&self, path: &[u8]) -> Option<Mapping> {
-    if let Some((archive_path, member_name)) = split_archive_path(path) {
-        let map = super::mmap(Path::new(archive_path))?;
-        let archive = object::read::archive::ArchiveFile::parse(&map).ok()?;
-        let mut members = archive.members();
-        while let Ok(Some(member)) = members.next() {
-            if member.name() == member_name.as_bytes() {
-                let (macho, data) = find_header(Bytes(member.data()))?;
-                let endian = macho.endian().ok()?;
-                let object = Object::parse(macho, endian, data)?;
-                let stash = Stash::new();
-                let inner = super::cx(&stash, object)?;
-                return Some((mk!(Mapping { map, inner, stash }), object_addr));
+    fn object_mapping(&mut self, path: &[u8]) -> Option<Mapping> {
+        if let Some((archive_path, member_name)) = split_archive_path(path) {
+            let (offset, size) = self.archive_member_range(archive_path, member_name)?;
+            let map = super::mmap(Path::new(archive_path))?;
+            let data = Bytes(&map).read_bytes_at(offset as usize, size as usize).ok()?;
+            let (macho, data) = find_header(data)?;
+            let endian = macho.endian().ok()?;
+            let object = Object::parse(macho, endian, data, data)?;
+            let stash = Stash::new();
+            let inner = super::cx(&stash, object)?;
+            Some(mk!(Mapping { map, inner, stash }))
+        } else {
+            let map = super::mmap(Path::new(path))?;
+            let (macho, data) = find_header(Bytes(&map))?;
+            let endian = macho.endian().ok()?;
+            let object = Object::parse(macho, endian, data, data)?;
+            let stash = Stash::new();
+            let inner = super::cx(&stash, object)?;
+            Some(mk!(Mapping { map, inner, stash }))
+        }
+    }
+
+    // Returns the `(offset, size)` of `member_name` within `archive_path`,
+    // building and caching a full member index the first time this
+    // archive is seen so later members from the same archive skip the
+    // linear `members()` scan. The archive still has to be `mmap`'d here
+    // (and again by the caller above to build the member's `Mapping`):
+    // `Mapping::map` is a uniquely owned `Mmap`, so an already-mapped
+    // archive can't be cached and handed out to multiple `Mapping`s
+    // without widening that field to something cloneable/refcounted.
+    fn archive_member_range(&mut self, archive_path: &[u8], member_name: &[u8]) -> Option<(u64, u64)> {
+        if !self.archives.iter().any(|cache| cache.path == archive_path) {
+            let map = super::mmap(Path::new(archive_path))?;
+            let archive = object::read::archive::ArchiveFile::parse(&map[..]).ok()?;
+            let mut iter = archive.members();
+            let mut members = HashMap::new();
+            while let Ok(Some(member)) = iter.next() {
+                members.insert(member.name().to_vec(), member.file_range());
             }
+            self.archives.push(ArchiveCache {
+                path: archive_path.to_vec(),
+                members,
+            });
         }
-    } else {
-        let map = super::mmap(Path::new(path))?;
-        let (macho, data) = find_header(Bytes(&map))?;
-        let endian = macho.endian().ok()?;
-        let object = Object::parse(macho, endian, data)?;
-        let stash = Stash::new();
-        let inner = super::cx(&stash, object)?;
-        return Some((mk!(Mapping { map, inner, stash }), object_addr));
+
+        let cache = self.archives.iter().find(|cache| cache.path == archive_path)?;
+        let (offset, size) = *cache.members.get(member_name)?;
+        Some((offset, size))
     }
-    None
 }
 
 fn split_archive_path(path: &[u8]) -> Option<(&[u8], &[u8])> {
@@ -268,3 +581,417 @@ fn split_archive_path(path: &[u8]) -> Option<(&[u8], &[u8])> {
     let member = rest.strip_prefix(b'(')?.strip_suffix(b')')?;
     Some((archive, member))
 }
+
+// LC_FUNCTION_STARTS is a sequence of ULEB128 deltas, decoded cumulatively
+// starting from the vmaddr of the first executable __TEXT segment, giving
+// a sorted list of function start addresses.
+fn parse_function_starts(mut data: Bytes<'_>, base: u64) -> Vec<u64> {
+    let mut addr = base;
+    let mut starts = Vec::new();
+    while let Some(delta) = read_uleb128(&mut data) {
+        if delta == 0 {
+            break;
+        }
+        addr = addr.wrapping_add(delta);
+        starts.push(addr);
+    }
+    starts
+}
+
+const EXPORT_SYMBOL_FLAGS_REEXPORT: u64 = 0x8;
+
+fn read_uleb128(data: &mut Bytes<'_>) -> Option<u64> {
+    let mut result: u64 = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *data.read::<u8>().ok()?;
+        if shift < 64 {
+            result |= u64::from(byte & 0x7f) << shift;
+        }
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Some(result);
+        }
+        if shift >= 64 {
+            return None;
+        }
+    }
+}
+
+fn read_cstr<'a>(data: &mut Bytes<'a>) -> Option<&'a [u8]> {
+    let nul = data.0.iter().position(|&b| b == 0)?;
+    let s = &data.0[..nul];
+    data.read_bytes(nul + 1).ok()?;
+    Some(s)
+}
+
+// Walks the LC_DYLD_INFO export trie rooted at `offset` into `trie`,
+// emitting a `(name, address)` pair into `syms` for every terminal node
+// that has a non-empty name and isn't a re-export (re-exports are skipped).
+// Edge strings accumulate into `prefix` as we descend and are leaked into
+// the same `&'a [u8]` lifetime the nlist-derived names already use, since
+// (unlike nlist names) they don't live anywhere in the original image.
+fn walk_export_trie<'a>(
+    trie: Bytes<'a>,
+    base: u64,
+    offset: usize,
+    prefix: &mut Vec<u8>,
+    visited: &mut Vec<usize>,
+    syms: &mut Vec<(&'a [u8], u64)>,
+) -> Option<()> {
+    if offset >= trie.0.len() || visited.contains(&offset) || visited.len() > trie.0.len() {
+        return Some(());
+    }
+    visited.push(offset);
+
+    let mut node = trie.read_bytes_at(offset, trie.0.len() - offset).ok()?;
+    let terminal_size = read_uleb128(&mut node)?;
+    let mut terminal = node.read_bytes(terminal_size.try_into().ok()?).ok()?;
+    if terminal_size > 0 {
+        let flags = read_uleb128(&mut terminal)?;
+        if flags & EXPORT_SYMBOL_FLAGS_REEXPORT == 0 {
+            let address = read_uleb128(&mut terminal)?;
+            if !prefix.is_empty() {
+                let name: &'a [u8] = Box::leak(prefix.clone().into_boxed_slice());
+                syms.push((name, base.wrapping_add(address)));
+            }
+        }
+    }
+
+    // The child count and edges live in `node` right after the
+    // `terminal_size`-byte terminal block we just consumed above, not in
+    // `terminal` itself.
+    let child_count = *node.read::<u8>().ok()?;
+    for _ in 0..child_count {
+        let edge = read_cstr(&mut node)?;
+        let child_offset = read_uleb128(&mut node)?;
+        let prefix_len = prefix.len();
+        prefix.extend_from_slice(edge);
+        walk_export_trie(
+            trie,
+            base,
+            child_offset.try_into().ok()?,
+            prefix,
+            visited,
+            syms,
+        )?;
+        prefix.truncate(prefix_len);
+    }
+
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uleb128(values: &[u64]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for &v in values {
+            let mut value = v;
+            loop {
+                let mut byte = (value & 0x7f) as u8;
+                value >>= 7;
+                if value != 0 {
+                    byte |= 0x80;
+                }
+                out.push(byte);
+                if value == 0 {
+                    break;
+                }
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn read_uleb128_single_byte() {
+        let bytes = uleb128(&[0]);
+        let mut data = Bytes(&bytes);
+        assert_eq!(read_uleb128(&mut data), Some(0));
+
+        let bytes = uleb128(&[0x7f]);
+        let mut data = Bytes(&bytes);
+        assert_eq!(read_uleb128(&mut data), Some(0x7f));
+    }
+
+    #[test]
+    fn read_uleb128_multi_byte() {
+        let bytes = uleb128(&[624485]); // the canonical DWARF ULEB128 example
+        let mut data = Bytes(&bytes);
+        assert_eq!(read_uleb128(&mut data), Some(624485));
+    }
+
+    #[test]
+    fn read_uleb128_consumes_only_its_own_bytes() {
+        let bytes = uleb128(&[1, 2, 3]);
+        let mut data = Bytes(&bytes);
+        assert_eq!(read_uleb128(&mut data), Some(1));
+        assert_eq!(read_uleb128(&mut data), Some(2));
+        assert_eq!(read_uleb128(&mut data), Some(3));
+        assert_eq!(read_uleb128(&mut data), None);
+    }
+
+    #[test]
+    fn read_uleb128_truncated_is_none() {
+        let bytes = [0x80, 0x80];
+        let mut data = Bytes(&bytes);
+        assert_eq!(read_uleb128(&mut data), None);
+    }
+
+    #[test]
+    fn read_cstr_reads_up_to_and_past_nul() {
+        let bytes = b"hello\0world";
+        let mut data = Bytes(bytes);
+        assert_eq!(read_cstr(&mut data), Some(&b"hello"[..]));
+        assert_eq!(&*data.0, b"world");
+    }
+
+    #[test]
+    fn read_cstr_missing_nul_is_none() {
+        let bytes = b"no nul here";
+        let mut data = Bytes(bytes);
+        assert_eq!(read_cstr(&mut data), None);
+    }
+
+    // Builds a tiny two-node export trie equivalent to the one `dyld`
+    // produces for a dylib exporting a single symbol `_foo` at address
+    // `0x1000`, non-terminal root node first:
+    //   root:  terminal_size=0, child_count=1, edge="_foo" -> offset
+    //   child: terminal_size=N, flags=0, address=0x1000, child_count=0
+    fn single_export_trie() -> (Vec<u8>, usize) {
+        let mut terminal = Vec::new();
+        terminal.extend(uleb128(&[0])); // flags
+        terminal.extend(uleb128(&[0x1000])); // address
+
+        let mut child = Vec::new();
+        child.extend(uleb128(&[terminal.len() as u64]));
+        child.extend(&terminal);
+        child.push(0); // child_count
+
+        let mut root = Vec::new();
+        root.extend(uleb128(&[0])); // terminal_size (non-terminal root)
+        root.push(1); // child_count
+        root.extend_from_slice(b"_foo\0");
+        let child_offset = root.len() + 1; // + the uleb128 byte for child_offset below
+        root.extend(uleb128(&[child_offset as u64]));
+
+        root.extend(&child);
+        (root, child_offset)
+    }
+
+    #[test]
+    fn walk_export_trie_finds_non_terminal_root_symbol() {
+        let (trie, _child_offset) = single_export_trie();
+        let trie_bytes = Bytes(&trie);
+        let mut prefix = Vec::new();
+        let mut visited = Vec::new();
+        let mut syms = Vec::new();
+        walk_export_trie(trie_bytes, 0, 0, &mut prefix, &mut visited, &mut syms).unwrap();
+        assert_eq!(syms.len(), 1);
+        assert_eq!(syms[0].0, b"_foo");
+        assert_eq!(syms[0].1, 0x1000);
+    }
+
+    #[test]
+    fn walk_export_trie_applies_base_address() {
+        let (trie, _) = single_export_trie();
+        let trie_bytes = Bytes(&trie);
+        let mut prefix = Vec::new();
+        let mut visited = Vec::new();
+        let mut syms = Vec::new();
+        walk_export_trie(trie_bytes, 0x4000_0000, 0, &mut prefix, &mut visited, &mut syms).unwrap();
+        assert_eq!(syms, vec![(&b"_foo"[..], 0x4000_1000)]);
+    }
+
+    #[test]
+    fn walk_export_trie_skips_reexports() {
+        let mut terminal = Vec::new();
+        terminal.extend(uleb128(&[EXPORT_SYMBOL_FLAGS_REEXPORT])); // flags
+        terminal.extend(uleb128(&[0])); // ordinal, unused by the parser
+
+        let mut trie = Vec::new();
+        trie.extend(uleb128(&[terminal.len() as u64]));
+        trie.extend(&terminal);
+        trie.push(1); // child_count
+        trie.extend_from_slice(b"_bar\0");
+        trie.extend(uleb128(&[0])); // child offset: a self-loop back to the root, cut off by `visited`
+
+        let trie_bytes = Bytes(&trie);
+        let mut prefix = b"_bar".to_vec();
+        let mut visited = Vec::new();
+        let mut syms = Vec::new();
+        walk_export_trie(trie_bytes, 0, 0, &mut prefix, &mut visited, &mut syms).unwrap();
+        assert!(syms.is_empty());
+    }
+
+    #[test]
+    fn walk_export_trie_out_of_range_offset_is_noop() {
+        let trie = uleb128(&[0]);
+        let trie_bytes = Bytes(&trie);
+        let mut prefix = Vec::new();
+        let mut visited = Vec::new();
+        let mut syms = Vec::new();
+        assert!(walk_export_trie(trie_bytes, 0, 1000, &mut prefix, &mut visited, &mut syms).is_some());
+        assert!(syms.is_empty());
+    }
+
+    #[test]
+    fn parse_function_starts_accumulates_deltas_from_base() {
+        let bytes = uleb128(&[0x10, 0x20, 0x8]);
+        let starts = parse_function_starts(Bytes(&bytes), 0x1000);
+        assert_eq!(starts, vec![0x1010, 0x1030, 0x1038]);
+    }
+
+    #[test]
+    fn parse_function_starts_stops_at_zero_delta() {
+        let mut bytes = uleb128(&[0x10, 0]);
+        bytes.extend(uleb128(&[0x20])); // must not be reached
+        let starts = parse_function_starts(Bytes(&bytes), 0);
+        assert_eq!(starts, vec![0x10]);
+    }
+
+    #[test]
+    fn parse_function_starts_empty_input_is_empty() {
+        let starts = parse_function_starts(Bytes(&[]), 0x1000);
+        assert!(starts.is_empty());
+    }
+
+    #[test]
+    fn parse_function_starts_truncated_uleb128_stops_cleanly() {
+        // A lone continuation byte with no terminator is an invalid
+        // ULEB128, so `read_uleb128` returns `None` and the loop just ends
+        // instead of panicking or looping forever.
+        let bytes = [0x80];
+        let starts = parse_function_starts(Bytes(&bytes), 0x1000);
+        assert!(starts.is_empty());
+    }
+
+    #[test]
+    fn pick_fat_arch_ignores_mismatched_cputype() {
+        let desired = match desired_cpu() {
+            Some(cpu) => cpu,
+            // Only an Apple-platform target has a `desired_cpu`; nothing
+            // else to check `pick_fat_arch` against here.
+            None => return,
+        };
+        let other = desired.wrapping_add(1);
+        let archs = vec![(other, 0, 0x1000, 0x100)];
+        assert_eq!(pick_fat_arch(archs.into_iter()), None);
+    }
+
+    #[test]
+    fn pick_fat_arch_prefers_subtype_all_fallback() {
+        let desired = match desired_cpu() {
+            Some(cpu) => cpu,
+            None => return,
+        };
+        // Only run where there's an `_ALL` subtype to fall back to (every
+        // Apple-platform target this crate supports).
+        let all_subtype = match desired_cpu_subtype_all() {
+            Some(subtype) => subtype,
+            None => return,
+        };
+        // The `0x1234` slice can't match `desired_cpu_subtype()` (it's
+        // determined by the *test binary's* own header, not anything we
+        // control here). Whether the `_ALL` slice is an exact match for it
+        // or only the fallback, `pick_fat_arch` picks the slice tagged as
+        // the generic `_ALL` subtype, regardless of order.
+        let some_other_subtype = 0x1234;
+        let archs = vec![
+            (desired, some_other_subtype, 0x1000, 0x100),
+            (desired, all_subtype, 0x2000, 0x200),
+        ];
+        assert_eq!(pick_fat_arch(archs.into_iter()), Some((0x2000, 0x200)));
+    }
+
+    #[test]
+    fn pick_fat_arch_falls_back_to_first_match_without_all() {
+        let desired = match desired_cpu() {
+            Some(cpu) => cpu,
+            None => return,
+        };
+        let archs = vec![
+            (desired, 0x1234, 0x1000, 0x100),
+            (desired, 0x5678, 0x2000, 0x200),
+        ];
+        assert_eq!(pick_fat_arch(archs.into_iter()), Some((0x1000, 0x100)));
+    }
+
+    #[test]
+    fn pick_fat_arch_no_entries_is_none() {
+        if desired_cpu().is_none() {
+            return;
+        }
+        assert_eq!(pick_fat_arch(std::iter::empty()), None);
+    }
+
+    #[test]
+    fn slice_offset_within_container() {
+        let container = [1u8, 2, 3, 4, 5];
+        assert_eq!(slice_offset(&container, &container[2..4]), Some(2));
+        assert_eq!(slice_offset(&container, &container[..]), Some(0));
+        assert_eq!(slice_offset(&container, &container[5..]), Some(5));
+    }
+
+    #[test]
+    fn slice_offset_unrelated_buffer_is_none() {
+        let container = [1u8, 2, 3];
+        let other = [1u8, 2, 3];
+        assert_eq!(slice_offset(&container, &other[..]), None);
+    }
+
+    #[test]
+    fn slice_offset_empty_slice_inside_container() {
+        // An empty slice gets the same range check as any other: one whose
+        // pointer lands inside `container` is reported at that offset --
+        // callers only ever check this against `image_data`, which is never
+        // empty in practice.
+        let container = [1u8, 2, 3];
+        assert_eq!(slice_offset(&container, &container[1..1]), Some(1));
+    }
+
+    #[test]
+    fn dyld_shared_cache_file_paths_finds_numbered_siblings() {
+        let dir = std::env::temp_dir().join(format!("backtrace-rs-test-siblings-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let primary = dir.join("dyld_shared_cache_arm64e");
+        std::fs::write(&primary, b"").unwrap();
+        std::fs::write(dir.join("dyld_shared_cache_arm64e.01"), b"").unwrap();
+        std::fs::write(dir.join("dyld_shared_cache_arm64e.02"), b"").unwrap();
+        std::fs::write(dir.join("dyld_shared_cache_arm64e.symbols"), b"").unwrap();
+        // Unrelated file in the same directory that shares only the
+        // `dyld_shared_cache_` part of `primary`'s file name, not the whole
+        // name followed by a `.`, so it must not match.
+        std::fs::write(dir.join("dyld_shared_cache_x86_64"), b"").unwrap();
+
+        let paths = dyld_shared_cache_file_paths(&primary);
+        let names: Vec<_> = paths
+            .iter()
+            .map(|p| p.file_name().unwrap().to_str().unwrap().to_owned())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "dyld_shared_cache_arm64e",
+                "dyld_shared_cache_arm64e.01",
+                "dyld_shared_cache_arm64e.02",
+                "dyld_shared_cache_arm64e.symbols",
+            ]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dyld_shared_cache_file_paths_no_siblings_is_just_primary() {
+        let dir = std::env::temp_dir().join(format!("backtrace-rs-test-lone-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let primary = dir.join("dyld_shared_cache_x86_64");
+        std::fs::write(&primary, b"").unwrap();
+
+        assert_eq!(dyld_shared_cache_file_paths(&primary), vec![primary.clone()]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}